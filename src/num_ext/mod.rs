@@ -1,27 +1,89 @@
-use num::bigint::{ToBigUint, RandBigInt, BigUint};
-use num::{Zero, One};
-use num::integer::Integer;
+use num::bigint::{ToBigUint, ToBigInt, RandBigInt, BigUint, BigInt};
+use num::{Zero, One, Signed, ToPrimitive};
+use num::integer::{Integer, Roots};
 use rand::thread_rng;
-use std::sync::{Arc, mpsc};
+use std::sync::{Arc, Once, mpsc};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::thread;
 
+/// Upper bound (inclusive) on the small primes to pre-sieve candidates against before paying
+/// for a full Miller-Rabin round. 65536 covers a few thousand primes, which is enough to knock out
+/// the vast majority of composite candidates with cheap trial division.
+const SMALL_PRIME_SIEVE_LIMIT: u64 = 65536;
+
+/// The first few thousand primes, generated once via a sieve of Eratosthenes and cached
+/// for the lifetime of the process. Used to trial-divide candidates before falling back
+/// to the expensive Miller-Rabin test.
+fn small_primes() -> &'static Vec<u64> {
+    static INIT: Once = Once::new();
+    static mut PRIMES: Option<Vec<u64>> = None;
+    unsafe {
+        INIT.call_once(|| {
+            PRIMES = Some(sieve_of_eratosthenes(SMALL_PRIME_SIEVE_LIMIT));
+        });
+        PRIMES.as_ref().unwrap()
+    }
+}
+
+/// Classic sieve of Eratosthenes, returning every prime <= limit.
+fn sieve_of_eratosthenes(limit: u64) -> Vec<u64> {
+    let limit = limit as usize;
+    let mut is_composite = vec![false; limit + 1];
+    let mut primes = Vec::new();
+    let mut i = 2usize;
+    while i <= limit {
+        if !is_composite[i] {
+            primes.push(i as u64);
+            let mut j = i * i;
+            while j <= limit {
+                is_composite[j] = true;
+                j += i;
+            }
+        }
+        i += 1;
+    }
+    primes
+}
+
 /// Cryptographically useful extensions to the provided BigUint functionality.
 pub trait BigUintCrypto {
     /// Find the next prime from the current BigUint
     fn next_prime(&self) -> BigUint;
 
-    /// Threaded version of the next_prime() operation, this is not recommended for use because it
-    /// is slower than the unthreaded version.
+    /// Threaded version of the next_prime() operation. Tests several candidates ahead in
+    /// parallel (one per available core) and returns the smallest prime found, rather
+    /// than parallelizing the Miller-Rabin witnesses for a single candidate.
     fn next_prime_threaded(&self) -> BigUint;
     /// use the extended euclidean algorithm to solve for (g,x,y) given (a,b) such that
-    /// g = gcd(a,b) = a*x + b*y.
-    fn gcdext(&self, other: &BigUint) -> (BigUint, BigUint, BigUint);
+    /// g = gcd(a,b) = a*x + b*y. The Bezout coefficients x and y may be negative, so they
+    /// are returned as BigInt even though a, b and g are unsigned.
+    fn gcdext(&self, other: &BigUint) -> (BigUint, BigInt, BigInt);
 
     /// Is this number a prime number. Uses a probablistic function to determine primality.
     fn is_prime(n: &BigUint) -> bool;
 
+    /// Is this number a prime number, using the Baillie-PSW test. This combines a strong
+    /// Fermat test to base 2 with a strong Lucas test; no composite has ever been found
+    /// that passes both, making this stronger in practice than a fixed number of
+    /// Miller-Rabin rounds while still running in deterministic time.
+    fn is_prime_bpsw(n: &BigUint) -> bool;
+
     /// perform the function (base^exponent) % modulus using exponentiation by sqauring
     fn mod_exp(base: &BigUint, exponent: &BigUint, modulus: &BigUint) -> BigUint;
+
+    /// Find a^-1 mod modulus, i.e. the value x such that (a*x) % modulus == 1. Returns None
+    /// when a and modulus are not coprime, since no such inverse exists in that case. This is
+    /// the missing ingredient for computing an RSA private exponent from e and phi(n).
+    fn mod_inverse(a: &BigUint, modulus: &BigUint) -> Option<BigUint>;
+
+    /// Generate a random prime with exactly `bits` bits, running `rounds` Miller-Rabin
+    /// rounds against each candidate. More rounds trade speed for confidence.
+    fn gen_prime(bits: usize, rounds: usize) -> BigUint;
+
+    /// Generate a random safe prime with exactly `bits` bits: a prime p such that
+    /// (p-1)/2 is also prime. Safe primes are what Diffie-Hellman and strong RSA moduli
+    /// want. `rounds` is passed through to the underlying primality tests.
+    fn gen_safe_prime(bits: usize, rounds: usize) -> BigUint;
 }
 
 impl BigUintCrypto for BigUint {
@@ -33,15 +95,21 @@ impl BigUintCrypto for BigUint {
         next_prime_helper(&self.clone(), true)
     }
 
-    fn gcdext(&self, other: &BigUint) -> (BigUint, BigUint, BigUint) {
-
-        (Zero::zero(), Zero::zero(), Zero::zero())
+    fn gcdext(&self, other: &BigUint) -> (BigUint, BigInt, BigInt) {
+        let a = self.to_bigint().unwrap();
+        let b = other.to_bigint().unwrap();
+        let (g, x, y) = extended_gcd(&a, &b);
+        (g.to_biguint().unwrap(), x, y)
     }
 
     fn is_prime(n: &BigUint) -> bool {
         is_prime_helper(n, false)
     }
 
+    fn is_prime_bpsw(n: &BigUint) -> bool {
+        is_prime_bpsw_helper(n)
+    }
+
     fn mod_exp(base: &BigUint, exponent: &BigUint, modulus: &BigUint) -> BigUint {
         let zero = Zero::zero();
         let one: BigUint = One::one();
@@ -58,38 +126,398 @@ impl BigUintCrypto for BigUint {
         }
         result
     }
+
+    fn mod_inverse(a: &BigUint, modulus: &BigUint) -> Option<BigUint> {
+        let (g, x, _) = extended_gcd(&a.to_bigint().unwrap(), &modulus.to_bigint().unwrap());
+        if g != One::one() {
+            return None;
+        }
+        let modulus_int = modulus.to_bigint().unwrap();
+        let inverse = ((x % &modulus_int) + &modulus_int) % &modulus_int;
+        inverse.to_biguint()
+    }
+
+    fn gen_prime(bits: usize, rounds: usize) -> BigUint {
+        loop {
+            let mut candidate = thread_rng().gen_biguint(bits);
+            force_bit_bounds(&mut candidate, bits);
+            let candidate = advance_to_probable_prime(candidate, rounds);
+            if candidate.bits() == bits {
+                return candidate;
+            }
+            // Advancing past the next prime pushed us over the requested bit length
+            // (only possible when no prime lies between the candidate and 2^bits - 1); redraw.
+        }
+    }
+
+    fn gen_safe_prime(bits: usize, rounds: usize) -> BigUint {
+        let two = 2.to_biguint().unwrap();
+        loop {
+            let p = BigUint::gen_prime(bits, rounds);
+            let sophie_germain = (&p - &One::one()) / &two;
+            if is_probable_prime_rounds(&sophie_germain, rounds) {
+                return p;
+            }
+        }
+    }
+}
+
+/// Force `candidate` to have exactly `bits` bits: set the top bit so the value has full
+/// width, and the bottom bit so it is odd.
+fn force_bit_bounds(candidate: &mut BigUint, bits: usize) {
+    let one: BigUint = One::one();
+    let top_bit = &one << (bits - 1);
+    if &*candidate < &top_bit {
+        *candidate = &*candidate + &top_bit;
+    }
+    if candidate.is_even() {
+        *candidate = &*candidate + &one;
+    }
+}
+
+/// Trial-divide by the small-prime sieve, then run `rounds` rounds of Miller-Rabin.
+/// Used by the `gen_prime`/`gen_safe_prime` family, which want the round count exposed
+/// to the caller rather than the fixed 100 rounds `is_prime` uses.
+fn is_probable_prime_rounds(n: &BigUint, rounds: usize) -> bool {
+    match trial_divide_small_primes(n) {
+        Some(result) => result,
+        None => miller_rabin(n, rounds, false),
+    }
+}
+
+/// Shared wheel-based search for the smallest probable prime >= `start` (the caller has
+/// already forced `start` to be odd). Maintains `start % p` for every small prime `p` and
+/// advances each residue by 2 in lockstep with the candidate, so a candidate with a small
+/// factor can be ruled out without ever doing a big-integer division. Used both by
+/// `next_prime_helper`, with `rounds` fixed at the 100 rounds `is_prime` uses, and by the
+/// `gen_prime`/`gen_safe_prime` family, which want the round count configurable.
+fn advance_to_probable_prime(start: BigUint, rounds: usize) -> BigUint {
+    let one: BigUint = One::one();
+    let two = 2.to_biguint().unwrap();
+    let mut candidate = start;
+
+    let primes = small_primes();
+    let mut residues: Vec<u64> = primes.iter()
+        .map(|&p| (&candidate % &p.to_biguint().unwrap()).to_u64().unwrap())
+        .collect();
+
+    loop {
+        // A candidate that is itself one of the sieved primes is prime outright.
+        let is_small_prime = primes.iter().zip(residues.iter()).any(|(&p, &r)| {
+            r == 0 && candidate == p.to_biguint().unwrap()
+        });
+        if is_small_prime {
+            return candidate;
+        }
+
+        let has_small_factor = primes.iter().zip(residues.iter()).any(|(&p, &r)| {
+            r == 0 && candidate != p.to_biguint().unwrap()
+        });
+
+        // The wheel above already proved no small prime divides `candidate`, so go
+        // straight to Miller-Rabin instead of paying for another full trial division
+        // through `is_probable_prime_rounds`.
+        if !has_small_factor && candidate > one && miller_rabin(&candidate, rounds, false) {
+            return candidate;
+        }
+
+        candidate = &candidate + &two;
+        for (residue, &p) in residues.iter_mut().zip(primes.iter()) {
+            *residue = (*residue + 2) % p;
+        }
+    }
+}
+
+/// Iterative extended Euclidean algorithm. Returns (g, x, y) such that
+/// g = gcd(a,b) = a*x + b*y, tracking the Bezout coefficients as signed BigInt
+/// since they can go negative even when a and b are both non-negative.
+fn extended_gcd(a: &BigInt, b: &BigInt) -> (BigInt, BigInt, BigInt) {
+    let (mut old_r, mut r) = (a.clone(), b.clone());
+    let (mut old_s, mut s): (BigInt, BigInt) = (One::one(), Zero::zero());
+    let (mut old_t, mut t): (BigInt, BigInt) = (Zero::zero(), One::one());
+
+    while !r.is_zero() {
+        let quotient = &old_r / &r;
+
+        let new_r = &old_r - &quotient * &r;
+        old_r = r;
+        r = new_r;
+
+        let new_s = &old_s - &quotient * &s;
+        old_s = s;
+        s = new_s;
+
+        let new_t = &old_t - &quotient * &t;
+        old_t = t;
+        t = new_t;
+    }
+
+    (old_r, old_s, old_t)
 }
 
 fn next_prime_helper(n: &BigUint, thread: bool) -> BigUint {
+    if thread {
+        return next_prime_parallel(n, default_workers());
+    }
+
+    let one: BigUint = One::one();
+    let two = 2.to_biguint().unwrap();
+    let mut candidate = n.clone();
+    if &candidate % &two == Zero::zero() {
+        candidate = &candidate + &one;
+    } else {
+        candidate = &candidate + &two;
+    }
+
+    advance_to_probable_prime(candidate, 100)
+}
+
+/// Threaded `next_prime`: test `workers` candidate odd numbers ahead of `n` at once, each
+/// on its own thread, and return the smallest prime among them. If none of the batch are
+/// prime, advance past the whole batch and try the next one. This replaces spawning a
+/// thread per Miller-Rabin witness for a single candidate (which `next_prime_threaded`
+/// used to do) with spreading the threads across independent candidates instead, which
+/// is what actually lets it beat the unthreaded version.
+fn next_prime_parallel(n: &BigUint, workers: usize) -> BigUint {
+    let workers = workers.max(1);
     let one: BigUint = One::one();
     let two = 2.to_biguint().unwrap();
-    let mut next_prime = n.clone();
-    if &next_prime % &two == Zero::zero() {
-        next_prime = &next_prime + &one;
+
+    let mut batch_start = n.clone();
+    if &batch_start % &two == Zero::zero() {
+        batch_start = &batch_start + &one;
     } else {
-        next_prime = &next_prime + &two;
+        batch_start = &batch_start + &two;
     }
-    while !is_prime_helper(&next_prime, thread) {
-        next_prime = &next_prime + &two;
+
+    loop {
+        let candidates: Vec<BigUint> = (0..workers)
+            .map(|i| &batch_start + &(2 * i).to_biguint().unwrap())
+            .collect();
+
+        let (tx, rx) = mpsc::channel();
+        for (i, candidate) in candidates.iter().cloned().enumerate() {
+            let tx = tx.clone();
+            thread::spawn(move || {
+                let is_prime = is_prime_helper(&candidate, false);
+                let _ = tx.send((i, is_prime));
+            });
+        }
+        drop(tx);
+
+        let mut is_prime = vec![false; workers];
+        for _ in 0..workers {
+            if let Ok((i, result)) = rx.recv() {
+                is_prime[i] = result;
+            }
+        }
+
+        if let Some(i) = is_prime.iter().position(|&p| p) {
+            return candidates[i].clone();
+        }
+
+        batch_start = &batch_start + &(2 * workers).to_biguint().unwrap();
     }
-    next_prime
+}
+
+/// Trial-divide `n` by the small-prime sieve. Returns `Some(true)` if `n` is one of the
+/// sieved primes itself, `Some(false)` if a sieved prime divides `n`, or `None` if `n`
+/// survives every small prime and still needs a real primality test.
+fn trial_divide_small_primes(n: &BigUint) -> Option<bool> {
+    let two = 2.to_biguint().unwrap();
+    if *n < two {
+        return Some(false);
+    }
+    for &p in small_primes().iter() {
+        let p_big = p.to_biguint().unwrap();
+        if *n == p_big {
+            return Some(true);
+        }
+        if n % &p_big == Zero::zero() {
+            return Some(false);
+        }
+    }
+    None
 }
 
 fn is_prime_helper(n: &BigUint, thread: bool) -> bool {
+    match trial_divide_small_primes(n) {
+        Some(result) => result,
+        None => miller_rabin(n, 100, thread),
+    }
+}
+
+fn is_prime_bpsw_helper(n: &BigUint) -> bool {
+    if let Some(result) = trial_divide_small_primes(n) {
+        return result;
+    }
+    // A perfect square is never prime, and also makes the Lucas D search below loop
+    // forever (the Jacobi symbol of a square is never -1), so rule it out up front.
+    if is_perfect_square(n) {
+        return false;
+    }
+
+    let one: BigUint = One::one();
     let two = 2.to_biguint().unwrap();
-    let three = 3.to_biguint().unwrap();
-    if *n == three || *n == two {
-        return true;
+    let mut d: BigUint = n - &one;
+    let mut s: BigUint = Zero::zero();
+    while d.is_even() {
+        d = d >> 1;
+        s = s + &one;
     }
-    if *n < two || n % two == Zero::zero() {
+    let ctx = MontgomeryCtx::new(n);
+    if !is_strong_probable_prime_base(&ctx, n, &d, &s, &two) {
         return false;
     }
-    miller_rabin(n, 100, thread)
+
+    is_strong_lucas_probable_prime(n)
+}
+
+/// Integer square root check: is `n` a perfect square?
+fn is_perfect_square(n: &BigUint) -> bool {
+    if n.is_zero() {
+        return true;
+    }
+    let root = n.sqrt();
+    &root * &root == *n
+}
+
+/// Jacobi symbol (a|n) for odd positive n, using the standard reciprocity algorithm.
+/// `a` may be negative (the Lucas parameter search below needs that).
+fn jacobi(a: &BigInt, n: &BigUint) -> i32 {
+    let n_int = n.to_bigint().unwrap();
+    let mut a = a.mod_floor(&n_int);
+    let mut n = n_int;
+    let mut result = 1;
+
+    while !a.is_zero() {
+        while a.is_even() {
+            a = &a / 2;
+            let r = (&n % 8.to_bigint().unwrap()).to_i64().unwrap();
+            if r == 3 || r == 5 {
+                result = -result;
+            }
+        }
+
+        std::mem::swap(&mut a, &mut n);
+
+        if (&a % 4.to_bigint().unwrap()).to_i64().unwrap() == 3
+            && (&n % 4.to_bigint().unwrap()).to_i64().unwrap() == 3 {
+            result = -result;
+        }
+
+        a = a.mod_floor(&n);
+    }
+
+    if n == One::one() { result } else { 0 }
+}
+
+/// Divide `x` by 2 modulo the odd modulus `n`. `x` may be unreduced or negative; the result
+/// is congruent to x/2 (mod n) but is not itself reduced into [0, n).
+fn mod_halve(x: &BigInt, n: &BigInt) -> BigInt {
+    if x.is_even() {
+        x / 2
+    } else {
+        (x + n) / 2
+    }
 }
+
+/// Strong Lucas probable-prime test with Selfridge parameters, the second half of the
+/// Baillie-PSW test. `n` must already be known odd, non-square and free of small factors.
+fn is_strong_lucas_probable_prime(n: &BigUint) -> bool {
+    let n_int = n.to_bigint().unwrap();
+
+    // Selfridge's method: scan D = 5, -7, 9, -11, 13, ... until (D|n) == -1.
+    let mut abs_d: i64 = 5;
+    let mut sign: i64 = 1;
+    let d;
+    loop {
+        let candidate = (abs_d * sign).to_bigint().unwrap();
+        match jacobi(&candidate, n) {
+            -1 => { d = candidate; break; }
+            0 => {
+                // gcd(|candidate|, n) > 1: n is composite unless it equals the factor itself.
+                let abs_candidate = candidate.abs().to_biguint().unwrap();
+                return *n == abs_candidate;
+            }
+            _ => {}
+        }
+        abs_d += 2;
+        sign = -sign;
+    }
+
+    let p: BigInt = One::one();
+    let q: BigInt = (&One::one() - &d) / 4.to_bigint().unwrap();
+
+    // n + 1 = d' * 2^r with d' odd.
+    let mut d_prime = &n_int + &One::one();
+    let mut r: u32 = 0;
+    while d_prime.is_even() {
+        d_prime = &d_prime / 2;
+        r += 1;
+    }
+
+    // Compute U_{d'}, V_{d'}, Q^{d'} (mod n) by processing the bits of d' from the
+    // second-most-significant down to the least-significant, using the doubling
+    // identities U_{2k} = U_k*V_k, V_{2k} = V_k^2 - 2*Q^k, and the odd-step identities
+    // U_{2k+1} = (P*U_{2k} + V_{2k})/2, V_{2k+1} = (D*U_{2k} + P*V_{2k})/2.
+    let bits = bits_msb_first(&d_prime);
+    let (mut u, mut v, mut qk): (BigInt, BigInt, BigInt) = (One::one(), p.clone(), q.clone());
+
+    for &bit in &bits[1..] {
+        u = (&u * &v).mod_floor(&n_int);
+        v = (&v * &v - 2 * &qk).mod_floor(&n_int);
+        qk = (&qk * &qk).mod_floor(&n_int);
+
+        if bit {
+            let new_u = mod_halve(&(&p * &u + &v), &n_int).mod_floor(&n_int);
+            let new_v = mod_halve(&(&d * &u + &p * &v), &n_int).mod_floor(&n_int);
+            u = new_u;
+            v = new_v;
+            qk = (&qk * &q).mod_floor(&n_int);
+        }
+    }
+
+    if u.is_zero() {
+        return true;
+    }
+
+    for _ in 0..r {
+        if v.is_zero() {
+            return true;
+        }
+        v = (&v * &v - 2 * &qk).mod_floor(&n_int);
+        qk = (&qk * &qk).mod_floor(&n_int);
+    }
+
+    false
+}
+
+/// Bits of a non-negative BigInt, most significant first.
+fn bits_msb_first(n: &BigInt) -> Vec<bool> {
+    let two = 2.to_bigint().unwrap();
+    let mut bits = Vec::new();
+    let mut acc = n.clone();
+    if acc.is_zero() {
+        return vec![false];
+    }
+    while !acc.is_zero() {
+        bits.push((&acc % &two) == One::one());
+        acc = &acc / &two;
+    }
+    bits.reverse();
+    bits
+}
+
+/// Number of workers to fan a parallel primality search out across when the caller
+/// doesn't specify one. Defaults to the platform's available parallelism.
+fn default_workers() -> usize {
+    thread::available_parallelism().map(|n| n.get()).unwrap_or(1)
+}
+
 /// n must be greater than 3 and k indicates the number of rounds
-fn miller_rabin(n: &BigUint, k: usize, thread: bool) -> bool{
+fn miller_rabin(n: &BigUint, k: usize, thread: bool) -> bool {
     let one: BigUint = One::one();
-    let (tx, rx) = mpsc::channel();
 
     let mut d: BigUint = n - &One::one();
     let mut s: BigUint = Zero::zero();
@@ -98,66 +526,207 @@ fn miller_rabin(n: &BigUint, k: usize, thread: bool) -> bool{
         s = s + &one;
     }
     if thread {
-        let shared_n = Arc::new(n.clone());
-        let shared_d = Arc::new(d);
-        let shared_s = Arc::new(s);
+        miller_rabin_parallel(n, &d, &s, k, default_workers())
+    } else {
+        miller_rabin_thread(n, &d, &s, k)
+    }
+}
 
-        // miller rabin lends itself to being concurrent since a is completely random
-        // here we spawn multiple threads to help speed up the process
-        for _ in 0..8 {
-            let tx = tx.clone();
-            //let thread_n = n.clone();
-            let shared_d = shared_d.clone();
-            let shared_s = shared_s.clone();
-            let shared_n = shared_n.clone();
-            thread::spawn(move || {
-                let in_n = shared_n;
-                let in_d = shared_d;
-                let in_s = shared_s;
-                let result = miller_rabin_thread(&in_n, &in_d, &in_s, k/8);
-                tx.send(result);
-                });
+/// Parallel Miller-Rabin across a configurable number of `workers`, splitting the `k`
+/// rounds between them as evenly as possible. Workers share a `cancelled` flag that is
+/// set the moment any worker finds a composite witness, so the rest stop at their next
+/// round instead of running every remaining round to completion, and the receiving loop
+/// returns as soon as the first composite result arrives rather than waiting on every
+/// worker's channel send.
+fn miller_rabin_parallel(n: &BigUint, d: &BigUint, s: &BigUint, k: usize, workers: usize) -> bool {
+    let workers = workers.max(1).min(k.max(1));
+    let (tx, rx) = mpsc::channel();
+    let cancelled = Arc::new(AtomicBool::new(false));
+    let shared_n = Arc::new(n.clone());
+    let shared_d = Arc::new(d.clone());
+    let shared_s = Arc::new(s.clone());
+    let shared_ctx = Arc::new(MontgomeryCtx::new(n));
+
+    let base_rounds = k / workers;
+    let extra_rounds = k % workers;
+
+    for worker in 0..workers {
+        let rounds = base_rounds + if worker < extra_rounds { 1 } else { 0 };
+        if rounds == 0 {
+            continue;
         }
 
-        let mut prime = true;
-        for _ in 0..8 {
-            if !rx.recv().ok().expect("A thread failed") {
-                prime = false;
+        let tx = tx.clone();
+        let shared_n = shared_n.clone();
+        let shared_d = shared_d.clone();
+        let shared_s = shared_s.clone();
+        let shared_ctx = shared_ctx.clone();
+        let cancelled = cancelled.clone();
+
+        thread::spawn(move || {
+            let two = 2.to_biguint().unwrap();
+            for _ in 0..rounds {
+                if cancelled.load(Ordering::Relaxed) {
+                    return;
+                }
+                let a = thread_rng().gen_biguint_range(&two, &(&*shared_n - &two));
+                if !is_strong_probable_prime_base(&shared_ctx, &shared_n, &shared_d, &shared_s, &a) {
+                    cancelled.store(true, Ordering::Relaxed);
+                    let _ = tx.send(false);
+                    return;
+                }
             }
+            let _ = tx.send(true);
+        });
+    }
+    drop(tx);
+
+    // Require an explicit `true` from every worker rather than inferring success once
+    // the channel disconnects: if a worker panics before sending (e.g. an unexpected
+    // arithmetic failure), its `tx` clone is dropped silently, and treating that the same
+    // as "all workers finished" would report an unconfirmed candidate as probably prime.
+    let mut completed = 0;
+    for _ in 0..workers {
+        match rx.recv() {
+            Ok(false) => return false,
+            Ok(true) => completed += 1,
+            Err(_) => break,
         }
-        prime
-    } else {
-        return miller_rabin_thread(n, &d, &s, k);
     }
+    completed == workers
 }
 
 fn miller_rabin_thread(n: &BigUint, d: &BigUint, s: &BigUint, k: usize) -> bool {
     let one: BigUint = One::one();
     let two: BigUint = &one + &one;
 
+    // n is fixed across all k witnesses, so build the Montgomery context once and
+    // amortize its setup cost (computing R, R^2 mod n and -n^-1 mod R) over every round.
+    let ctx = MontgomeryCtx::new(n);
+
     for _ in 0..k {
         //println!("loop {} of {}", j, k);
         let a = thread_rng().gen_biguint_range(&two, &(n - &two));
-        let mut x = mod_exp(&a, d, n);
-        //let mut x = two.clone();
-        if (x == one) || (x == (n - &one)) {
-            continue;
+        if !is_strong_probable_prime_base(&ctx, n, d, s, &a) {
+            return false;
         }
+    }
+    true
+}
 
-        // Use a while loop instead of for here because range does not accept BigUint
-        let mut i: BigUint = Zero::zero();
-        loop  {
-            x = mod_exp(&x, &two, n);
-            if x == one || i == (s - &one) {
-                return false;
-            }
-            if x == (n - &one) {
-                break;
+/// The strong probable-prime (Miller-Rabin) test for a single witness `a`, given the
+/// decomposition `n - 1 = d * 2^s`. Shared by the random-witness loops above and the
+/// fixed base-2 check used by the Baillie-PSW test. Runs entirely in Montgomery form via
+/// `ctx`: 1 and n-1 are moved into Montgomery form and compared there, so nothing is
+/// converted back to the normal domain.
+fn is_strong_probable_prime_base(ctx: &MontgomeryCtx, n: &BigUint, d: &BigUint, s: &BigUint, a: &BigUint) -> bool {
+    let one: BigUint = One::one();
+    let n_minus_one = n - &one;
+
+    let one_mont = ctx.to_mont(&one);
+    let n_minus_one_mont = ctx.to_mont(&n_minus_one);
+
+    let mut x_mont = ctx.pow_mont(a, d);
+    if x_mont == one_mont || x_mont == n_minus_one_mont {
+        return true;
+    }
+
+    // Use a manual loop instead of for here because range does not accept BigUint
+    let mut i: BigUint = Zero::zero();
+    loop {
+        x_mont = ctx.mont_mul(&x_mont, &x_mont);
+        if x_mont == one_mont || i == (s - &one) {
+            return false;
+        }
+        if x_mont == n_minus_one_mont {
+            return true;
+        }
+        i = i + &one;
+    }
+}
+
+/// Context for Montgomery-form modular arithmetic modulo a fixed odd `modulus`,
+/// precomputed once and reused across many multiplications/exponentiations. Replaces the
+/// full big-integer `%` that plain `mod_exp` performs on every squaring and multiply
+/// with a cheap REDC (shift + multiply-add), following the approach `crypto-bigint` takes.
+struct MontgomeryCtx {
+    modulus: BigUint,
+    /// R = 2^r_bits, with r_bits chosen as the bit length of the modulus.
+    r_bits: usize,
+    r: BigUint,
+    /// R - 1, a bitmask for the low r_bits bits. REDC uses this in place of `% R` so
+    /// reducing mod R is a cheap truncation instead of a full-width division.
+    r_mask: BigUint,
+    /// R^2 mod modulus, used to move values into Montgomery form.
+    r2: BigUint,
+    /// -modulus^-1 mod R, the constant REDC needs to cancel the low bits of modulus.
+    m_prime: BigUint,
+}
+
+impl MontgomeryCtx {
+    /// Build a Montgomery context for `modulus`, which must be odd (composite moduli with
+    /// small factors are already rejected by the small-prime sieve before we get here).
+    fn new(modulus: &BigUint) -> MontgomeryCtx {
+        let r_bits = modulus.bits();
+        let one: BigUint = One::one();
+        let r = &one << r_bits;
+        let r_mask = &r - &one;
+        let r2 = (&r * &r) % modulus;
+        let m_inv = BigUint::mod_inverse(modulus, &r)
+            .expect("Montgomery modulus must be odd");
+        let m_prime = &r - &m_inv;
+
+        MontgomeryCtx { modulus: modulus.clone(), r_bits, r, r_mask, r2, m_prime }
+    }
+
+    /// REDC(t) = t * R^-1 mod modulus, for any t < R * modulus. Reducing mod R is done
+    /// with a bitmask rather than `%`, since R is a power of two and masking is what
+    /// actually makes this cheaper than the division `mod_exp` pays on every squaring.
+    fn redc(&self, t: &BigUint) -> BigUint {
+        let t_low = t & &self.r_mask;
+        let m = (&t_low * &self.m_prime) & &self.r_mask;
+        let u = (t + &m * &self.modulus) >> self.r_bits;
+        if u >= self.modulus { &u - &self.modulus } else { u }
+    }
+
+    /// Move `a` into Montgomery form: a*R mod modulus.
+    fn to_mont(&self, a: &BigUint) -> BigUint {
+        self.redc(&(a * &self.r2))
+    }
+
+    /// Move `a_mont` back out of Montgomery form.
+    fn from_mont(&self, a_mont: &BigUint) -> BigUint {
+        self.redc(a_mont)
+    }
+
+    /// Multiply two Montgomery-form values, returning a Montgomery-form result.
+    fn mont_mul(&self, a_mont: &BigUint, b_mont: &BigUint) -> BigUint {
+        self.redc(&(a_mont * b_mont))
+    }
+
+    /// Square-and-multiply exponentiation performed entirely in Montgomery form,
+    /// returning the Montgomery-form result (left as-is so callers doing further
+    /// multiplications, like the Miller-Rabin squaring loop, can avoid round-tripping).
+    fn pow_mont(&self, base: &BigUint, exponent: &BigUint) -> BigUint {
+        let zero = Zero::zero();
+        let two = 2.to_biguint().unwrap();
+        let mut result = self.to_mont(&One::one());
+        let mut base_mont = self.to_mont(base);
+        let mut exp_acc = exponent.clone();
+        while exp_acc > zero {
+            if &exp_acc % &two == One::one() {
+                result = self.mont_mul(&result, &base_mont);
             }
-            i = i + &one;
+            exp_acc = exp_acc >> 1;
+            base_mont = self.mont_mul(&base_mont, &base_mont);
         }
+        result
+    }
+
+    /// Full modular exponentiation, converting the result back out of Montgomery form.
+    fn mont_pow(&self, base: &BigUint, exponent: &BigUint) -> BigUint {
+        self.from_mont(&self.pow_mont(base, exponent))
     }
-    true
 }
 
 fn mod_exp(base: &BigUint, exponent: &BigUint, modulus: &BigUint) -> BigUint {
@@ -179,9 +748,10 @@ fn mod_exp(base: &BigUint, exponent: &BigUint, modulus: &BigUint) -> BigUint {
 
 #[cfg(test)]
 mod test_BigUint_crypto {
-    use super::{BigUintCrypto, mod_exp, miller_rabin};
-    use num::bigint::{ToBigUint, RandBigInt, BigUint};
+    use super::{BigUintCrypto, mod_exp, miller_rabin, MontgomeryCtx};
+    use num::bigint::{ToBigUint, ToBigInt, RandBigInt, BigUint};
     use num::One;
+    use num::integer::Integer;
     use rand::thread_rng;
     use test::Bencher;
     use std::sync::{Arc, mpsc};
@@ -219,6 +789,35 @@ mod test_BigUint_crypto {
         assert!(mod_exp(&base, &exponent, &modulus) == expected_result);
     }
 
+    #[test]
+    fn gcdext_test() {
+        let a = 240.to_biguint().unwrap();
+        let b = 46.to_biguint().unwrap();
+
+        let (g, x, y) = a.gcdext(&b);
+
+        assert!(g == 2.to_biguint().unwrap());
+        assert!(a.to_bigint().unwrap() * x + b.to_bigint().unwrap() * y == g.to_bigint().unwrap());
+    }
+
+    #[test]
+    fn mod_inverse_test() {
+        let e = 17.to_biguint().unwrap();
+        let phi = 3120.to_biguint().unwrap();
+
+        let d = BigUint::mod_inverse(&e, &phi).unwrap();
+
+        assert!((e * d) % phi == One::one());
+    }
+
+    #[test]
+    fn mod_inverse_not_coprime_test() {
+        let a = 6.to_biguint().unwrap();
+        let modulus = 9.to_biguint().unwrap();
+
+        assert!(BigUint::mod_inverse(&a, &modulus).is_none());
+    }
+
     #[test]
     fn is_prime_test() {
         let known_prime = BigUint::
@@ -227,6 +826,69 @@ mod test_BigUint_crypto {
         assert!(BigUint::is_prime(&known_prime));
     }
 
+    #[test]
+    fn gen_prime_test() {
+        let prime = BigUint::gen_prime(128, 20);
+
+        assert!(prime.bits() == 128);
+        assert!(BigUint::is_prime_bpsw(&prime));
+    }
+
+    #[test]
+    fn gen_safe_prime_test() {
+        let two = 2.to_biguint().unwrap();
+        let prime = BigUint::gen_safe_prime(64, 20);
+        let sophie_germain = (&prime - &One::one()) / &two;
+
+        assert!(BigUint::is_prime_bpsw(&prime));
+        assert!(BigUint::is_prime_bpsw(&sophie_germain));
+    }
+
+    #[test]
+    fn mont_pow_test() {
+        let base = 4.to_biguint().unwrap();
+        let exponent = 13.to_biguint().unwrap();
+        let modulus = 497.to_biguint().unwrap();
+
+        let ctx = MontgomeryCtx::new(&modulus);
+
+        assert!(ctx.mont_pow(&base, &exponent) == mod_exp(&base, &exponent, &modulus));
+    }
+
+    #[bench]
+    fn bench_mont_pow(bench: &mut Bencher) {
+        let a = thread_rng().gen_biguint(300);
+        let b = thread_rng().gen_biguint(300);
+        let one: BigUint = One::one();
+        let mut c = thread_rng().gen_biguint(300);
+        if c.is_even() {
+            c = &c + &one;
+        }
+        let ctx = MontgomeryCtx::new(&c);
+
+        bench.iter(|| {
+            ctx.mont_pow(&a, &b);
+            });
+    }
+
+    #[test]
+    fn is_prime_bpsw_test() {
+        let known_prime = BigUint::
+        parse_bytes("4829837983753984028472098472089547098728675098723407520875297".as_bytes(), 10).unwrap();
+
+        assert!(BigUint::is_prime_bpsw(&known_prime));
+    }
+
+    #[test]
+    #[should_panic(expected = "assertion failed")]
+    fn is_prime_bpsw_test_failuire() {
+        let not_prime = BigUint::
+        parse_bytes("359709793871987301975987296195681798740165298740176567105918720469720137416098423"
+        .as_bytes(), 10).unwrap();
+
+        assert!(BigUint::is_prime_bpsw(&not_prime));
+    }
+
     #[test]
     #[should_panic(expected = "assertion failed")]
     fn is_prime_test_failuire() {